@@ -1,14 +1,38 @@
 use socket2::{Domain, Socket, Type};
 use std::net::UdpSocket;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
-use std::{
-    collections::HashMap,
-    net::{IpAddr, Ipv4Addr, SocketAddr},
-};
 use thingbuf::mpsc::blocking::SendRef;
 use thiserror::Error;
 
-use crate::{Count, Payload, PayloadBlock, BACKLOG_BUFFER_PAYLOADS, UDP_PAYLOAD};
+use crate::metrics::Metrics;
+use crate::{
+    CapturedBlock, Count, Meta, Payload, BACKLOG_BUFFER_PAYLOADS, BLOCK_PAYLOADS, UDP_PAYLOAD,
+};
+
+/// How many datagrams we try to pull out of the kernel in a single `recvmmsg` syscall.
+const BATCH: usize = 64;
+
+/// Slots in the reorder ring. Two count ranges are live at once — the current
+/// block's not-yet-drained tail `[oldest, oldest + BACKLOG_BUFFER_PAYLOADS)` and the
+/// incoming future window `[oldest + n, oldest + n + BACKLOG_BUFFER_PAYLOADS)`. They
+/// differ by exactly one block, so we give the ring two halves selected by the count's
+/// block-generation parity; because adjacent generations always differ in parity the
+/// two ranges land in opposite halves and can never alias.
+const REORDER_SLOTS: usize = 2 * BACKLOG_BUFFER_PAYLOADS;
+
+/// Number of 64-bit words in the reorder-buffer occupancy bitmap.
+const PRESENT_WORDS: usize = REORDER_SLOTS / 64;
+
+/// Number of 64-bit words needed to track the fill state of a whole block.
+const FILL_WORDS: usize = BLOCK_PAYLOADS / 64;
+
+/// How many consecutive fully-dropped blocks of far-out counts it takes to decide
+/// the upstream counter has jumped; reaching this count resynchronizes to it.
+const RESYNC_DEAD_BLOCKS: usize = 2;
 
 #[derive(Error, Debug)]
 /// Errors that can be produced from captures
@@ -17,16 +41,38 @@ pub enum Error {
     SizeMismatch(usize),
     #[error("Failed to set the recv buffer size. We tried to set {expected}, but found {found}. Check sysctl net.core.rmem_max")]
     SetRecvBufferFailed { expected: usize, found: usize },
+    #[error("recvmmsg failed: {0}")]
+    RecvMmsg(#[source] std::io::Error),
 }
 
 pub struct Capture {
     pub sock: UdpSocket,
     pub buffer: Payload,
-    pub backlog: HashMap<Count, Payload>,
+    // Reorder buffer for out-of-order (future) payloads. A count maps to a slot via
+    // `reorder_slot`, which keys on `count % BACKLOG_BUFFER_PAYLOADS` within a half
+    // chosen by block-generation parity; `present` tracks which slots hold live data
+    // and `reorder_meta[slot].count` tags the slot so a wrapped index can never be
+    // mistaken for a stale neighbour.
+    reorder: Box<[Payload; REORDER_SLOTS]>,
+    reorder_meta: Box<[Meta; REORDER_SLOTS]>,
+    present: [u64; PRESENT_WORDS],
     pub drops: usize,
     pub processed: usize,
+    pub resyncs: usize,
+    pub metrics: Arc<Metrics>,
     first_payload: bool,
     oldest_count: Count,
+    // Consecutive fully-dropped blocks, used to detect a stream discontinuity.
+    dead_blocks: usize,
+    // Staging for the batched `recvmmsg` path. `stage` holds `BATCH` contiguous
+    // payload slots; `names` receives the per-datagram source address; `iovs` and
+    // `msgs` are the parallel scatter/gather arrays the kernel fills. They point
+    // into `stage`/`names`, which live on the heap, so the pointers stay valid once
+    // `Capture` is moved out of `new`.
+    stage: Vec<u8>,
+    names: Vec<libc::sockaddr_storage>,
+    _iovs: Vec<libc::iovec>,
+    msgs: Vec<libc::mmsghdr>,
 }
 
 impl Capture {
@@ -52,14 +98,52 @@ impl Capture {
         }
         // Replace the socket2 socket with a std socket
         let sock = socket.into();
+
+        // Build the batched-receive staging area. One iovec per slot points at the
+        // matching `UDP_PAYLOAD`-sized window of the contiguous staging buffer, and
+        // one `sockaddr_storage` per slot receives that datagram's source address.
+        let mut stage = vec![0u8; BATCH * UDP_PAYLOAD];
+        // SAFETY: `sockaddr_storage` is POD; the kernel fills it on each recv.
+        let mut names: Vec<libc::sockaddr_storage> =
+            (0..BATCH).map(|_| unsafe { std::mem::zeroed() }).collect();
+        let mut iovs = Vec::with_capacity(BATCH);
+        for i in 0..BATCH {
+            iovs.push(libc::iovec {
+                iov_base: stage[i * UDP_PAYLOAD..].as_mut_ptr() as *mut libc::c_void,
+                iov_len: UDP_PAYLOAD,
+            });
+        }
+        let mut msgs = Vec::with_capacity(BATCH);
+        for (iov, name) in iovs.iter_mut().zip(names.iter_mut()) {
+            // SAFETY: `msghdr` is plain POD; we overwrite every field we use below.
+            let mut hdr: libc::msghdr = unsafe { std::mem::zeroed() };
+            hdr.msg_iov = iov as *mut libc::iovec;
+            hdr.msg_iovlen = 1;
+            hdr.msg_name = name as *mut _ as *mut libc::c_void;
+            hdr.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            msgs.push(libc::mmsghdr {
+                msg_hdr: hdr,
+                msg_len: 0,
+            });
+        }
+
         Ok(Self {
             sock,
             buffer: [0u8; UDP_PAYLOAD],
-            backlog: HashMap::with_capacity(BACKLOG_BUFFER_PAYLOADS),
+            reorder: Box::new([[0u8; UDP_PAYLOAD]; REORDER_SLOTS]),
+            reorder_meta: new_reorder_meta(),
+            present: [0u64; PRESENT_WORDS],
             drops: 0,
             processed: 0,
+            resyncs: 0,
+            metrics: Metrics::new(),
             first_payload: true,
             oldest_count: 0,
+            dead_blocks: 0,
+            stage,
+            names,
+            _iovs: iovs,
+            msgs,
         })
     }
 
@@ -72,74 +156,239 @@ impl Capture {
         }
     }
 
+    /// Pull up to `n` datagrams out of the kernel with a single `recvmmsg` syscall.
+    ///
+    /// The payloads land back-to-back in `self.stage`; slot `i` occupies
+    /// `stage[i * UDP_PAYLOAD .. (i + 1) * UDP_PAYLOAD]`, its source address lands in
+    /// `self.names[i]`, and its received length is recorded in `self.msgs[i].msg_len`.
+    /// Returns the number of datagrams actually filled, which may be fewer than `n`
+    /// under low load — that is a short read, not a drop.
+    pub fn capture_batch(&mut self, n: usize) -> anyhow::Result<usize> {
+        let vlen = n.min(BATCH);
+        // Reset the per-call out params so a short read leaves no stale values behind.
+        for m in self.msgs[..vlen].iter_mut() {
+            m.msg_len = 0;
+            m.msg_hdr.msg_namelen =
+                std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        }
+        // SAFETY: `msgs` points at `iovs`/`names` which live on the heap and are
+        // sized for `vlen <= BATCH`. We block for at least one message.
+        let ret = unsafe {
+            libc::recvmmsg(
+                self.sock.as_raw_fd(),
+                self.msgs.as_mut_ptr(),
+                vlen as libc::c_uint,
+                libc::MSG_WAITFORONE,
+                ptr::null_mut(),
+            )
+        };
+        if ret < 0 {
+            return Err(Error::RecvMmsg(std::io::Error::last_os_error()).into());
+        }
+        Ok(ret as usize)
+    }
+
     pub fn capture_next_block(
         &mut self,
-        mut slot: SendRef<'_, PayloadBlock>,
+        mut slot: SendRef<'_, CapturedBlock>,
     ) -> anyhow::Result<(Duration, Duration)> {
-        let n = slot.len();
-        // Sneaky bit manipulation (all bits to 1 to set that the index corresponding with *that bit* needs to be filled)
-        let mut to_fill = n - 1;
+        let n = slot.payloads.len();
+        // Every slot starts out needing to be filled; a set bit means "still empty".
+        // A plain `usize` can't address a 32768-slot block, so use a real bitset, the
+        // same `[u64; _]` pattern as the reorder-buffer occupancy map.
+        let mut to_fill = [u64::MAX; FILL_WORDS];
 
         let mut packet_time = Duration::default();
 
-        // Fill every slot
-        for _ in 0..n {
-            // -- CAPTURE
-            // Capture an arbitrary payload
-            self.capture()?;
-            // Time starts now to benchmark processing perf
-            let now = Instant::now();
-            // Decode its count
-            let count = count(&self.buffer);
-            if self.first_payload {
-                self.oldest_count = count;
-                self.first_payload = false;
-            }
-            // -- SORT
-            // Find its position in this block
-            if count < self.oldest_count {
-                // Drop this payload, it happened in the past
-                self.drops += 1;
-            } else if count >= self.oldest_count + n as u64 {
-                // Packet is destined for the future, insert into reorder buf
-                self.backlog.insert(count, self.buffer);
-            } else {
-                let idx = (count - self.oldest_count) as usize;
-                // Remove this idx from the `to_fill` entry
-                to_fill &= !(1 << idx);
-                // Packet is for this block! Insert into it's position
-                slot[idx] = self.buffer;
-                self.processed += 1;
+        // Track discontinuity signals for this block: how many datagrams landed far
+        // outside the window (either direction) and the most recent such count, which
+        // becomes the resync target if the stream has truly jumped.
+        let start_processed = self.processed;
+        let mut far_out = 0usize;
+        let mut resync_target = self.oldest_count;
+
+        // Fill the block by pulling datagrams in batches rather than one syscall each
+        let mut received = 0;
+        while received < n {
+            let got = self.capture_batch(BATCH.min(n - received))?;
+            // One arrival timestamp for the batch is plenty for demux/telemetry.
+            let arrival = Instant::now();
+            for i in 0..got {
+                // Time starts now to benchmark processing perf
+                let now = Instant::now();
+                let off = i * UDP_PAYLOAD;
+                let len = self.msgs[i].msg_len as usize;
+                // A wrong-sized datagram is a malformed packet, not a real slot
+                if len != UDP_PAYLOAD {
+                    self.drops += 1;
+                    packet_time += now.elapsed();
+                    continue;
+                }
+                // Decode its count and source metadata
+                let count = count(&self.stage[off..off + UDP_PAYLOAD]);
+                let addr = sockaddr(&self.names[i]);
+                let meta = Meta {
+                    addr,
+                    recv_instant: arrival,
+                    len,
+                    count,
+                };
+                if self.first_payload {
+                    self.oldest_count = count;
+                    self.first_payload = false;
+                }
+                // -- SORT
+                // Find its position in this block
+                if count < self.oldest_count {
+                    // Drop this payload, it happened in the past
+                    self.drops += 1;
+                    // A count far behind the window is a discontinuity candidate
+                    if self.oldest_count - count > BACKLOG_BUFFER_PAYLOADS as u64 {
+                        far_out += 1;
+                        resync_target = count;
+                    }
+                } else if count >= self.oldest_count + n as u64 {
+                    // Packet is destined for the future. It must land within the
+                    // reorder window `[oldest + n, oldest + n + BACKLOG_BUFFER_PAYLOADS)`;
+                    // anything further ahead would alias a live slot, so it is a real
+                    // overflow and counts as a drop.
+                    if count >= self.oldest_count + n as u64 + BACKLOG_BUFFER_PAYLOADS as u64 {
+                        self.drops += 1;
+                        // A count far ahead of the window is a discontinuity candidate
+                        far_out += 1;
+                        resync_target = count;
+                    } else {
+                        let r = reorder_slot(count);
+                        // A slot already holding a different live count is a genuine
+                        // collision; count it as a drop rather than clobber the buffered
+                        // packet that is still waiting to be drained.
+                        if self.present_get(r) && self.reorder_meta[r].count != count {
+                            self.drops += 1;
+                        } else {
+                            self.reorder[r].copy_from_slice(&self.stage[off..off + UDP_PAYLOAD]);
+                            self.reorder_meta[r] = meta;
+                            self.present_set(r);
+                        }
+                    }
+                } else {
+                    let idx = (count - self.oldest_count) as usize;
+                    // Mark this idx as filled
+                    to_fill[idx / 64] &= !(1 << (idx % 64));
+                    // Packet is for this block! Insert into it's position
+                    slot.payloads[idx].copy_from_slice(&self.stage[off..off + UDP_PAYLOAD]);
+                    slot.meta[idx] = meta;
+                    self.processed += 1;
+                }
+                // Stop the timer and add to the block time
+                packet_time += now.elapsed();
             }
-            // Stop the timer and add to the block time
-            packet_time += now.elapsed();
+            received += got;
         }
         // Now we'll fill in gaps with past data, if we have it
         // Otherwise replace with zeros and increment the drop count
         let block_process = Instant::now();
-        for (idx, buf) in slot.iter_mut().enumerate() {
+        let CapturedBlock { payloads, meta } = &mut *slot;
+        for (idx, (buf, m)) in payloads.iter_mut().zip(meta.iter_mut()).enumerate() {
             // Check if this bit needs to be filled
-            if (to_fill >> idx) & 1 == 1 {
-                // Then either fill with data from the past, or set it as default
+            if (to_fill[idx / 64] >> (idx % 64)) & 1 == 1 {
+                // Then either fill with data from the reorder buffer, or set it as default
                 let count = idx as u64 + self.oldest_count;
-                if let Some(pl) = self.backlog.remove(&count) {
-                    buf.clone_from_slice(&pl);
+                let r = reorder_slot(count);
+                // Only consume the slot if it is occupied by *this* count
+                if self.present_get(r) && self.reorder_meta[r].count == count {
+                    buf.clone_from_slice(&self.reorder[r]);
+                    *m = self.reorder_meta[r].clone();
+                    self.present_clear(r);
                     self.processed += 1;
                 } else {
                     let mut pl = [0u8; UDP_PAYLOAD];
                     (pl[0..8]).clone_from_slice(&count.to_be_bytes());
                     buf.clone_from_slice(&pl);
+                    *m = Meta {
+                        count,
+                        ..Default::default()
+                    };
                     self.drops += 1;
                 }
             }
         }
         // Move the oldest count forward by the block size
         self.oldest_count += n as u64;
+
+        // Decide whether the upstream counter has jumped. A block in which nothing
+        // landed in-window, dominated by counts far outside it, is one symptom; we
+        // wait for a short run of them (a board reboot, clock reset, or long stall)
+        // before resynchronizing so a single hiccup doesn't flush a healthy buffer.
+        if self.processed == start_processed && far_out * 2 >= n {
+            self.dead_blocks += 1;
+        } else {
+            self.dead_blocks = 0;
+        }
+        if self.dead_blocks >= RESYNC_DEAD_BLOCKS {
+            // Stream discontinuity: flush the reorder buffer and re-anchor to the
+            // freshly observed count, exactly as `first_payload` does on startup.
+            self.present = [0u64; PRESENT_WORDS];
+            self.oldest_count = resync_target;
+            self.resyncs += 1;
+            self.dead_blocks = 0;
+        }
+
+        // Fold this block's counters into the rolling telemetry
+        self.metrics.record_block(
+            self.processed as u64,
+            self.drops as u64,
+            self.resyncs as u64,
+            self.backlog_occupancy() as u64,
+        );
+
         let block_time = block_process.elapsed();
         Ok((packet_time, block_time))
     }
+
+    /// Number of reorder-buffer slots currently holding buffered payloads.
+    pub fn backlog_occupancy(&self) -> usize {
+        self.present.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    fn present_get(&self, slot: usize) -> bool {
+        (self.present[slot / 64] >> (slot % 64)) & 1 == 1
+    }
+
+    fn present_set(&mut self, slot: usize) {
+        self.present[slot / 64] |= 1 << (slot % 64);
+    }
+
+    fn present_clear(&mut self, slot: usize) {
+        self.present[slot / 64] &= !(1 << (slot % 64));
+    }
 }
 
-fn count(pl: &Payload) -> Count {
+fn count(pl: &[u8]) -> Count {
     u64::from_be_bytes(pl[0..8].try_into().unwrap())
 }
+
+/// Map a count to its reorder-ring slot: `count % BACKLOG_BUFFER_PAYLOADS` within the
+/// half picked by the block generation's parity. Counts a block apart land in opposite
+/// halves, so the current-tail and future windows that are live together never alias.
+fn reorder_slot(count: Count) -> usize {
+    let half = (count / BLOCK_PAYLOADS as u64) % 2;
+    (half as usize) * BACKLOG_BUFFER_PAYLOADS + (count % BACKLOG_BUFFER_PAYLOADS as u64) as usize
+}
+
+/// Build a fresh reorder-metadata buffer on the heap.
+fn new_reorder_meta() -> Box<[Meta; REORDER_SLOTS]> {
+    let v: Vec<Meta> = (0..REORDER_SLOTS).map(|_| Meta::default()).collect();
+    v.into_boxed_slice()
+        .try_into()
+        .unwrap_or_else(|_| unreachable!("vec built with exactly REORDER_SLOTS elements"))
+}
+
+/// Decode the IPv4 source address the kernel wrote into a `sockaddr_storage`. The
+/// capture socket is always bound `AF_INET`, so we only ever see `sockaddr_in`.
+fn sockaddr(storage: &libc::sockaddr_storage) -> SocketAddr {
+    // SAFETY: the socket is `AF_INET`, so the storage holds a `sockaddr_in`.
+    let sin = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+    let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+    let port = u16::from_be(sin.sin_port);
+    SocketAddr::new(IpAddr::V4(ip), port)
+}
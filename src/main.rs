@@ -1,8 +1,12 @@
 mod capture;
+mod metrics;
+mod sink;
 
 use crate::capture::Capture;
+use crate::sink::{KafkaSink, Sink, StdoutSink};
 use anyhow::bail;
 use core_affinity::CoreId;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::time::Instant;
 use thingbuf::{mpsc::blocking::with_recycle, Recycle};
 
@@ -13,6 +17,7 @@ const BLOCK_PAYLOAD_POW: u32 = 15;
 const BLOCK_PAYLOADS: usize = 2usize.pow(BLOCK_PAYLOAD_POW);
 const BLOCKS_TO_SORT: usize = 512;
 const RING_BLOCKS: usize = 4;
+const METRICS_EVERY: usize = 32; // Blocks between rolling telemetry summary lines
 
 type Count = u64;
 
@@ -20,6 +25,42 @@ pub type Payload = [u8; UDP_PAYLOAD];
 
 pub type PayloadBlock = Box<[Payload; BLOCK_PAYLOADS]>;
 
+pub type MetaBlock = Box<[Meta; BLOCK_PAYLOADS]>;
+
+/// Per-packet metadata, tracked in a buffer parallel to the payload bytes so that
+/// downstream code can demultiplex by source, spot a misbehaving sender, or
+/// timestamp data without ever touching the payload itself.
+#[derive(Clone)]
+pub struct Meta {
+    /// The address the datagram arrived from.
+    pub addr: SocketAddr,
+    /// When we pulled the datagram out of the kernel.
+    pub recv_instant: Instant,
+    /// The number of payload bytes actually received.
+    pub len: usize,
+    /// The payload's decoded count.
+    pub count: Count,
+}
+
+impl Default for Meta {
+    fn default() -> Self {
+        Self {
+            addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            recv_instant: Instant::now(),
+            len: 0,
+            count: 0,
+        }
+    }
+}
+
+/// A sorted block of payloads together with the metadata buffer describing them.
+/// The two live in distinct allocations rather than being interleaved.
+#[derive(Clone)]
+pub struct CapturedBlock {
+    pub payloads: PayloadBlock,
+    pub meta: MetaBlock,
+}
+
 pub struct PayloadRecycle;
 
 impl PayloadRecycle {
@@ -28,16 +69,43 @@ impl PayloadRecycle {
     }
 }
 
-impl Recycle<PayloadBlock> for PayloadRecycle {
-    fn new_element(&self) -> PayloadBlock {
-        Box::new([[0u8; UDP_PAYLOAD]; BLOCK_PAYLOADS])
+fn new_meta_block() -> MetaBlock {
+    let v: Vec<Meta> = (0..BLOCK_PAYLOADS).map(|_| Meta::default()).collect();
+    v.into_boxed_slice()
+        .try_into()
+        .unwrap_or_else(|_| unreachable!("vec built with exactly BLOCK_PAYLOADS elements"))
+}
+
+impl Recycle<CapturedBlock> for PayloadRecycle {
+    fn new_element(&self) -> CapturedBlock {
+        CapturedBlock {
+            payloads: Box::new([[0u8; UDP_PAYLOAD]; BLOCK_PAYLOADS]),
+            meta: new_meta_block(),
+        }
     }
 
-    fn recycle(&self, _: &mut PayloadBlock) {
+    fn recycle(&self, _: &mut CapturedBlock) {
         // Do nothing, we will write to every position anyway
     }
 }
 
+/// Build the output sink from the environment. `KAFKA_BROKERS` switches on the Kafka
+/// publisher (`KAFKA_TOPIC`, `KAFKA_PARTITIONS` tune it); absent it, blocks are summed
+/// and timed on stdout.
+fn build_sink() -> anyhow::Result<Box<dyn Sink>> {
+    match std::env::var("KAFKA_BROKERS") {
+        Ok(brokers) => {
+            let topic = std::env::var("KAFKA_TOPIC").unwrap_or_else(|_| "grex".to_owned());
+            let num_partitions = std::env::var("KAFKA_PARTITIONS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(1);
+            Ok(Box::new(KafkaSink::new(&brokers, &topic, num_partitions)?))
+        }
+        Err(_) => Ok(Box::new(StdoutSink)),
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     // Bind this thread to a core that shares a NUMA node with the NIC
     if !core_affinity::set_for_current(CoreId { id: 8 }) {
@@ -56,49 +124,45 @@ fn main() -> anyhow::Result<()> {
         r.recv_ref();
     }
 
+    // Pick where sorted blocks go. A Kafka sink is used when brokers are configured,
+    // otherwise we fall back to the benchmark stdout sink.
+    let mut sink = build_sink()?;
+
     // Spawn a thread to "sink" the payloads
     std::thread::spawn(move || {
         core_affinity::set_for_current(CoreId { id: 9 });
-        // Create a "static" buffer for this thread so we don't alloc
-        let mut current_block = Box::new([[0u8; UDP_PAYLOAD]; BLOCK_PAYLOADS]);
         while let Some(block) = r.recv_ref() {
-            // Copy into thread memory and drop
-            current_block.clone_from(&block);
-            let now = Instant::now();
-            // Do some work, maybe add all the numbers together. This should take on order 35ms (overflowing, but we don't care yet)
-            let sum = current_block
-                .iter()
-                .fold(0u8, |x, y| x + y.iter().sum::<u8>());
-            println!("Sum - {sum}, Duration - {} ms", now.elapsed().as_millis())
+            if let Err(e) = sink.consume(&block) {
+                eprintln!("Sink error: {e}");
+            }
         }
     });
 
+    // Expose rolling telemetry via a lightweight scrape endpoint for monitoring
+    let metrics_addr = std::env::var("METRICS_ADDR").unwrap_or_else(|_| "0.0.0.0:9100".to_owned());
+    cap.metrics.clone().serve(&metrics_addr)?;
+
     // "Warm up" by capturing a ton of packets
     for _ in 0..WARMUP_PACKETS {
         cap.capture()?;
     }
 
-    // Sort N blocks, printing dropped packets
-    for _ in 0..BLOCKS_TO_SORT {
+    // Sort N blocks, emitting a rolling telemetry summary periodically
+    for i in 0..BLOCKS_TO_SORT {
         // First block to grab a reference to the next slot in the queue
         let slot = s.send_ref().unwrap();
 
-        // Fill a block
-        let (p, b) = cap.capture_next_block(slot)?;
-
-        // At this point, we'd send the "sorted" block to the next stage by dropping slot
-        // Print timing info
-        println!(
-            "Processing - {} us per packet\tBlock - {} us - Backlog {}",
-            p.as_micros() as f32 / BLOCK_PAYLOADS as f32,
-            b.as_micros(),
-            cap.backlog.len(),
-        );
+        // Fill a block; the sorted block is sent to the sink by dropping slot
+        cap.capture_next_block(slot)?;
+
+        if i % METRICS_EVERY == 0 {
+            println!("{}", cap.metrics.window_summary());
+        }
     }
 
     println!(
-        "Dropped {} packets while processing {} packets.",
-        cap.drops, cap.processed
+        "Dropped {} packets while processing {} packets ({} resyncs).",
+        cap.drops, cap.processed, cap.resyncs
     );
     println!(
         "That's a drop rate of {}%",
@@ -0,0 +1,131 @@
+use std::io::Write;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::UDP_PAYLOAD;
+
+/// Rolling capture telemetry. Counters are folded in once per block from
+/// `capture_next_block` and read concurrently by the scrape endpoint, so they live
+/// behind atomics. Rates are derived from the counters and the process start time.
+pub struct Metrics {
+    start: Instant,
+    processed: AtomicU64,
+    drops: AtomicU64,
+    resyncs: AtomicU64,
+    backlog: AtomicU64,
+    backlog_hwm: AtomicU64,
+    // Snapshot of the previous summary, for windowed (since-last-report) rates.
+    last_nanos: AtomicU64,
+    last_processed: AtomicU64,
+    last_drops: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            start: Instant::now(),
+            processed: AtomicU64::new(0),
+            drops: AtomicU64::new(0),
+            resyncs: AtomicU64::new(0),
+            backlog: AtomicU64::new(0),
+            backlog_hwm: AtomicU64::new(0),
+            last_nanos: AtomicU64::new(0),
+            last_processed: AtomicU64::new(0),
+            last_drops: AtomicU64::new(0),
+        })
+    }
+
+    /// Fold one finished block's cumulative counters in. Tracks the backlog
+    /// high-water mark so operators can tell whether the reorder buffer is sized
+    /// correctly for the observed reordering.
+    pub fn record_block(&self, processed: u64, drops: u64, resyncs: u64, backlog: u64) {
+        self.processed.store(processed, Relaxed);
+        self.drops.store(drops, Relaxed);
+        self.resyncs.store(resyncs, Relaxed);
+        self.backlog.store(backlog, Relaxed);
+        self.backlog_hwm.fetch_max(backlog, Relaxed);
+    }
+
+    /// Bandwidth over the whole run, derived from payload bytes per processed packet.
+    pub fn bytes_per_second(&self) -> f64 {
+        let secs = self.start.elapsed().as_secs_f64();
+        if secs == 0.0 {
+            0.0
+        } else {
+            (self.processed.load(Relaxed) * UDP_PAYLOAD as u64) as f64 / secs
+        }
+    }
+
+    /// A one-line summary covering the window since it was last called.
+    pub fn window_summary(&self) -> String {
+        let now = self.start.elapsed().as_nanos() as u64;
+        let prev = self.last_nanos.swap(now, Relaxed);
+        let dt = (now.saturating_sub(prev)) as f64 / 1e9;
+
+        let processed = self.processed.load(Relaxed);
+        let drops = self.drops.load(Relaxed);
+        let wp = processed - self.last_processed.swap(processed, Relaxed);
+        let wd = drops - self.last_drops.swap(drops, Relaxed);
+
+        let pps = if dt > 0.0 { wp as f64 / dt } else { 0.0 };
+        let mbps = pps * UDP_PAYLOAD as f64 / 1e6;
+        let window_drop = rate(wd, wp);
+        let total_drop = rate(drops, processed);
+
+        format!(
+            "{pps:.0} pkt/s  {mbps:.1} MB/s  drop {window_drop:.3}% (win) / {total_drop:.3}% (total)  \
+             backlog {}/{} hwm  resyncs {}",
+            self.backlog.load(Relaxed),
+            self.backlog_hwm.load(Relaxed),
+            self.resyncs.load(Relaxed),
+        )
+    }
+
+    /// Start a lightweight Prometheus scrape endpoint on `addr` so monitoring can
+    /// track the capture over time.
+    pub fn serve(self: Arc<Self>, addr: &str) -> std::io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let body = self.prometheus();
+                let resp = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len(),
+                );
+                let mut stream = stream;
+                let _ = stream.write_all(resp.as_bytes());
+            }
+        });
+        Ok(())
+    }
+
+    fn prometheus(&self) -> String {
+        format!(
+            concat!(
+                "grex_packets_processed_total {}\n",
+                "grex_packets_dropped_total {}\n",
+                "grex_resyncs_total {}\n",
+                "grex_backlog_occupancy {}\n",
+                "grex_backlog_high_water_mark {}\n",
+                "grex_bytes_per_second {}\n",
+            ),
+            self.processed.load(Relaxed),
+            self.drops.load(Relaxed),
+            self.resyncs.load(Relaxed),
+            self.backlog.load(Relaxed),
+            self.backlog_hwm.load(Relaxed),
+            self.bytes_per_second(),
+        )
+    }
+}
+
+fn rate(part: u64, rest: u64) -> f64 {
+    let total = part + rest;
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * part as f64 / total as f64
+    }
+}
@@ -0,0 +1,94 @@
+use std::time::Instant;
+
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+
+use crate::CapturedBlock;
+
+/// A consumer of sorted blocks. Implementors get each block (payloads plus its
+/// parallel metadata) in order and are free to forward, persist, or summarize it.
+pub trait Sink {
+    fn consume(&mut self, block: &CapturedBlock) -> anyhow::Result<()>;
+}
+
+/// The original benchmark sink: sum the block and print how long it took. Useful for
+/// measuring the copy/sort path without a downstream dependency.
+pub struct StdoutSink;
+
+impl Sink for StdoutSink {
+    fn consume(&mut self, block: &CapturedBlock) -> anyhow::Result<()> {
+        let now = Instant::now();
+        // Do some work, maybe add all the numbers together. This should take on order 35ms (overflowing, but we don't care yet)
+        let sum = block
+            .payloads
+            .iter()
+            .fold(0u8, |x, y| x + y.iter().sum::<u8>());
+        println!("Sum - {sum}, Duration - {} ms", now.elapsed().as_millis());
+        Ok(())
+    }
+}
+
+/// Publishes each sorted block to Kafka. The block's starting count selects the
+/// partition so that consumers can parallelize across time-contiguous block ranges.
+pub struct KafkaSink {
+    producer: FutureProducer,
+    runtime: tokio::runtime::Runtime,
+    topic: String,
+    num_partitions: i32,
+}
+
+impl KafkaSink {
+    pub fn new(brokers: &str, topic: &str, num_partitions: i32) -> anyhow::Result<Self> {
+        if num_partitions < 1 {
+            anyhow::bail!("KafkaSink requires num_partitions >= 1, got {num_partitions}");
+        }
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("message.timeout.ms", "5000")
+            .create()?;
+        // The producer's send future needs a reactor; a current-thread runtime is
+        // plenty since the sink already runs on its own dedicated thread.
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            producer,
+            runtime,
+            topic: topic.to_owned(),
+            num_partitions,
+        })
+    }
+}
+
+impl Sink for KafkaSink {
+    fn consume(&mut self, block: &CapturedBlock) -> anyhow::Result<()> {
+        // Publish one record per datagram, keyed by its count, so no
+        // single message approaches librdkafka's `message.max.bytes` limit (a whole
+        // block is ~256 MiB and would be rejected outright). Enqueue the batch first,
+        // then await delivery.
+        // The block's starting count picks the partition, so each partition carries
+        // contiguous time ranges; the per-record count stays as the message key.
+        let partition = (block.meta[0].count % self.num_partitions as u64) as i32;
+        let mut pending = Vec::with_capacity(block.payloads.len());
+        for (payload, meta) in block.payloads.iter().zip(block.meta.iter()) {
+            let key = meta.count.to_be_bytes();
+            let record = FutureRecord::to(&self.topic)
+                .payload(&payload[..])
+                .key(&key[..])
+                .partition(partition);
+            let fut = self
+                .producer
+                .send_result(record)
+                .map_err(|(e, _)| anyhow::anyhow!("kafka enqueue failed: {e}"))?;
+            pending.push(fut);
+        }
+        self.runtime.block_on(async {
+            for fut in pending {
+                fut.await
+                    .map_err(|_| anyhow::anyhow!("kafka delivery canceled"))?
+                    .map_err(|(e, _)| anyhow::anyhow!("kafka send failed: {e}"))?;
+            }
+            Ok::<(), anyhow::Error>(())
+        })
+    }
+}